@@ -0,0 +1,221 @@
+//! A batch configuration builder that creates and configures several cgroup subsystems in one
+//! call, instead of one `create`/`set_value` pair per limit.
+
+use std::fmt;
+use std::io;
+
+use nix::unistd::Pid;
+
+use crate::{Cgroup, CgroupName, CgroupVersion};
+
+/// Builds one or more cgroups and applies a batch of limits across their subsystems in a single
+/// [`build`] call.
+///
+/// Today every limit means its own `create_dir`, `set_value` call (and, on v2, its own
+/// `cgroup.subtree_control` write) with separate error handling. `CgroupBuilder` lets a caller
+/// state every desired limit once and get back either the created [`Cgroup`]s or a single
+/// [`CgroupConfigurationError`] naming which key failed.
+///
+/// # Example
+///
+/// ```no_run
+/// use cgroups_fs::{CgroupBuilder, CgroupName};
+///
+/// let name = CgroupName::new("my-container");
+/// let cgroups = CgroupBuilder::new(&name)
+///     .memory_limit(256 * 1024 * 1024)
+///     .cpu_quota(50_000, 100_000)
+///     .pids_max(64)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// [`build`]: #method.build
+/// [`Cgroup`]: struct.Cgroup.html
+#[derive(Debug)]
+pub struct CgroupBuilder<'a> {
+    cgroup_name: &'a CgroupName,
+    limits: Vec<(&'static str, &'static str, String)>,
+    attach: Option<Pid>,
+}
+
+impl<'a> CgroupBuilder<'a> {
+    /// Starts building a configuration for `cgroup_name`.
+    pub fn new(cgroup_name: &'a CgroupName) -> Self {
+        Self {
+            cgroup_name,
+            limits: Vec::new(),
+            attach: None,
+        }
+    }
+
+    /// Limits memory usage to `bytes` (`memory.limit_in_bytes` on v1, `memory.max` on v2).
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        let key = match self.cgroup_name.version() {
+            CgroupVersion::V1 => "memory.limit_in_bytes",
+            CgroupVersion::V2 => "memory.max",
+        };
+        self.limits.push(("memory", key, bytes.to_string()));
+        self
+    }
+
+    /// Limits CPU time to `quota_us` out of every `period_us` microseconds (the separate
+    /// `cpu.cfs_quota_us`/`cpu.cfs_period_us` on v1, the combined `cpu.max` on v2).
+    pub fn cpu_quota(mut self, quota_us: u64, period_us: u64) -> Self {
+        match self.cgroup_name.version() {
+            CgroupVersion::V1 => {
+                self.limits
+                    .push(("cpu", "cpu.cfs_period_us", period_us.to_string()));
+                self.limits
+                    .push(("cpu", "cpu.cfs_quota_us", quota_us.to_string()));
+            }
+            CgroupVersion::V2 => {
+                self.limits
+                    .push(("cpu", "cpu.max", format!("{} {}", quota_us, period_us)));
+            }
+        }
+        self
+    }
+
+    /// Limits the number of tasks to `max` (`pids.max`, the same key on both versions).
+    pub fn pids_max(mut self, max: u64) -> Self {
+        self.limits.push(("pids", "pids.max", max.to_string()));
+        self
+    }
+
+    /// Attaches `pid` to every created cgroup once [`build`] has written all the limits.
+    ///
+    /// [`build`]: #method.build
+    pub fn attach(mut self, pid: Pid) -> Self {
+        self.attach = Some(pid);
+        self
+    }
+
+    /// Creates the needed cgroups (enabling their controllers in the parent's
+    /// `cgroup.subtree_control` on v2), writes every configured limit, attaches the process
+    /// passed to [`attach`], and returns the created [`Cgroup`]s, one per touched subsystem.
+    ///
+    /// [`attach`]: #method.attach
+    /// [`Cgroup`]: struct.Cgroup.html
+    pub fn build(self) -> Result<Vec<Cgroup>, CgroupConfigurationError> {
+        let mut subsystems: Vec<&'static str> = Vec::new();
+        for (subsystem, _, _) in &self.limits {
+            if !subsystems.contains(subsystem) {
+                subsystems.push(subsystem);
+            }
+        }
+
+        // On v2 every subsystem resolves to the same unified directory (`Cgroup::new` ignores
+        // `subsystem` in the path on v2), so it must only be `create`d once; later subsystems
+        // just need their controller enabled in the already-existing parent's
+        // `cgroup.subtree_control`, which `Cgroup::create` would otherwise redo a `create_dir`
+        // for and fail with `AlreadyExists`.
+        let mut unified_dir_created = false;
+        let mut created: Vec<(&'static str, Cgroup)> = Vec::new();
+        for subsystem in subsystems {
+            let cgroup = Cgroup::new(self.cgroup_name, subsystem);
+            match self.cgroup_name.version() {
+                CgroupVersion::V1 => {
+                    cgroup
+                        .create()
+                        .map_err(|source| CgroupConfigurationError::new(subsystem, None, source))?;
+                }
+                CgroupVersion::V2 => {
+                    if unified_dir_created {
+                        cgroup.enable_controller_on_parent().map_err(|source| {
+                            CgroupConfigurationError::new(subsystem, None, source)
+                        })?;
+                    } else {
+                        cgroup.create().map_err(|source| {
+                            CgroupConfigurationError::new(subsystem, None, source)
+                        })?;
+                        unified_dir_created = true;
+                    }
+                }
+            }
+            created.push((subsystem, cgroup));
+        }
+
+        for (subsystem, key, value) in &self.limits {
+            let cgroup = &created
+                .iter()
+                .find(|(created_subsystem, _)| created_subsystem == subsystem)
+                .expect("a cgroup was created for every subsystem referenced by a limit")
+                .1;
+            cgroup
+                .set_raw_value(key, value)
+                .map_err(|source| CgroupConfigurationError::new(subsystem, Some(key), source))?;
+        }
+
+        if let Some(pid) = self.attach {
+            // `add_process` moves the whole process via `cgroup.procs`; on v1 every subsystem
+            // has its own directory and needs its own attach, but on v2 they all share the same
+            // unified directory, so attaching once is enough (and avoids redundant writes).
+            match self.cgroup_name.version() {
+                CgroupVersion::V1 => {
+                    for (subsystem, cgroup) in &created {
+                        cgroup.add_process(pid).map_err(|source| {
+                            CgroupConfigurationError::new(subsystem, None, source)
+                        })?;
+                    }
+                }
+                CgroupVersion::V2 => {
+                    if let Some((subsystem, cgroup)) = created.first() {
+                        cgroup.add_process(pid).map_err(|source| {
+                            CgroupConfigurationError::new(subsystem, None, source)
+                        })?;
+                    }
+                }
+            }
+        }
+
+        Ok(created.into_iter().map(|(_, cgroup)| cgroup).collect())
+    }
+}
+
+/// The error returned by [`CgroupBuilder::build`] when any of its steps fails.
+///
+/// [`CgroupBuilder::build`]: struct.CgroupBuilder.html#method.build
+#[derive(Debug)]
+pub struct CgroupConfigurationError {
+    /// The subsystem being configured when the failure happened, e.g. `"memory"`.
+    pub subsystem: String,
+    /// The control file key that failed to write. `None` if the failure happened while creating
+    /// the cgroup or attaching a process to it, rather than while writing a limit.
+    pub key: Option<String>,
+    /// The underlying I/O error.
+    pub source: io::Error,
+}
+
+impl CgroupConfigurationError {
+    fn new(subsystem: &str, key: Option<&str>, source: io::Error) -> Self {
+        Self {
+            subsystem: subsystem.to_string(),
+            key: key.map(str::to_string),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for CgroupConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(
+                f,
+                "cgroup configuration failed setting {:?} on subsystem {:?}: {}",
+                key, self.subsystem, self.source
+            ),
+            None => write!(
+                f,
+                "cgroup configuration failed on subsystem {:?}: {}",
+                self.subsystem, self.source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CgroupConfigurationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
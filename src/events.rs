@@ -0,0 +1,195 @@
+//! Memory pressure and out-of-memory event notifications.
+//!
+//! Lets a caller block on kernel-signalled memory events instead of busy-polling
+//! [`Cgroup::get_value`]: on v1 this is `cgroup.event_control` wired to an `eventfd`, on v2 it is
+//! `inotify` watching the counters in `memory.events`.
+//!
+//! [`Cgroup::get_value`]: ../struct.Cgroup.html#method.get_value
+
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use nix::sys::eventfd::{eventfd, EfdFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use crate::{Cgroup, CgroupVersion};
+
+/// A memory event reported by an [`EventStream`].
+///
+/// [`EventStream`]: struct.EventStream.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryEvent {
+    /// The registered usage threshold was crossed, see [`Cgroup::watch_memory_threshold`].
+    ///
+    /// [`Cgroup::watch_memory_threshold`]: ../struct.Cgroup.html#method.watch_memory_threshold
+    Threshold,
+    /// The cgroup ran out of memory, see [`Cgroup::watch_oom`].
+    ///
+    /// [`Cgroup::watch_oom`]: ../struct.Cgroup.html#method.watch_oom
+    Oom,
+}
+
+/// A blocking stream of memory events for a single cgroup, returned by
+/// [`Cgroup::watch_memory_threshold`] and [`Cgroup::watch_oom`].
+///
+/// [`Cgroup::watch_memory_threshold`]: ../struct.Cgroup.html#method.watch_memory_threshold
+/// [`Cgroup::watch_oom`]: ../struct.Cgroup.html#method.watch_oom
+#[derive(Debug)]
+pub struct EventStream {
+    kind: EventStreamKind,
+}
+
+#[derive(Debug)]
+enum EventStreamKind {
+    V1EventFd {
+        event_fd: OwnedFd,
+        event: MemoryEvent,
+    },
+    V2Inotify {
+        inotify: Inotify,
+        events_path: PathBuf,
+        key: &'static str,
+        event: MemoryEvent,
+        last_count: u64,
+    },
+}
+
+impl EventStream {
+    /// Blocks until the kernel reports the next event, then returns it.
+    pub fn next_event(&mut self) -> io::Result<MemoryEvent> {
+        match &mut self.kind {
+            EventStreamKind::V1EventFd { event_fd, event } => {
+                // Reading an eventfd blocks until it has been signalled, and yields the number
+                // of signals coalesced since the last read (of no interest to us here).
+                let mut counter = [0u8; 8];
+                nix::unistd::read(&*event_fd, &mut counter).map_err(nix_to_io_error)?;
+                Ok(*event)
+            }
+            EventStreamKind::V2Inotify {
+                inotify,
+                events_path,
+                key,
+                event,
+                last_count,
+            } => loop {
+                inotify.read_events().map_err(nix_to_io_error)?;
+                let count = read_events_counter(events_path, key)?;
+                if count > *last_count {
+                    *last_count = count;
+                    return Ok(*event);
+                }
+            },
+        }
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = io::Result<MemoryEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+/// Converts a `nix` errno into the `io::Error` every other fallible method in this crate returns.
+fn nix_to_io_error(error: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(error as i32)
+}
+
+/// Reads a single counter out of the flat `key count` lines of a v2 `memory.events` file.
+fn read_events_counter(events_path: &Path, key: &str) -> io::Result<u64> {
+    let contents = fs::read_to_string(events_path)?;
+    Ok(contents
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? == key {
+                fields.next()?.parse().ok()
+            } else {
+                None
+            }
+        })
+        .unwrap_or(0))
+}
+
+/// Registers a v1 `cgroup.event_control` notification on `target_file` and returns the `eventfd`
+/// to read it from.
+fn register_v1_event(cgroup: &Cgroup, target_file: &str, args: Option<&str>) -> io::Result<OwnedFd> {
+    let event_fd = eventfd(0, EfdFlags::empty()).map_err(nix_to_io_error)?;
+    // The control file only needs to be open long enough for the kernel to read its fd below.
+    let target = fs::File::open(cgroup.root.join(target_file))?;
+    let mut value = format!("{} {}", event_fd.as_raw_fd(), target.as_raw_fd());
+    if let Some(args) = args {
+        value.push(' ');
+        value.push_str(args);
+    }
+    cgroup.set_raw_value("cgroup.event_control", value)?;
+    Ok(event_fd)
+}
+
+/// Registers a v2 `inotify` watch on `memory.events` and seeds `key`'s current counter value.
+fn register_v2_event(cgroup: &Cgroup, key: &'static str, event: MemoryEvent) -> io::Result<EventStream> {
+    let events_path = cgroup.root.join("memory.events");
+    let mut inotify = Inotify::init(InitFlags::empty()).map_err(nix_to_io_error)?;
+    inotify
+        .add_watch(&events_path, AddWatchFlags::IN_MODIFY)
+        .map_err(nix_to_io_error)?;
+    let last_count = read_events_counter(&events_path, key)?;
+    Ok(EventStream {
+        kind: EventStreamKind::V2Inotify {
+            inotify,
+            events_path,
+            key,
+            event,
+            last_count,
+        },
+    })
+}
+
+impl Cgroup {
+    /// Watches this cgroup's memory usage and yields an event whenever it crosses `bytes`.
+    ///
+    /// On v1 this registers for `memory.usage_in_bytes` threshold notifications via
+    /// `cgroup.event_control` and an `eventfd`. On v2 there is no equivalent threshold
+    /// notification, so `bytes` is written to `memory.high` and the `high` counter of
+    /// `memory.events` is watched via `inotify` instead — it increments every time usage
+    /// exceeds `bytes` and the kernel throttles the cgroup to reclaim memory.
+    pub fn watch_memory_threshold(&self, bytes: u64) -> io::Result<EventStream> {
+        match self.version {
+            CgroupVersion::V1 => {
+                let event_fd = register_v1_event(self, "memory.usage_in_bytes", Some(&bytes.to_string()))?;
+                Ok(EventStream {
+                    kind: EventStreamKind::V1EventFd {
+                        event_fd,
+                        event: MemoryEvent::Threshold,
+                    },
+                })
+            }
+            CgroupVersion::V2 => {
+                self.set_raw_value("memory.high", bytes.to_string())?;
+                register_v2_event(self, "high", MemoryEvent::Threshold)
+            }
+        }
+    }
+
+    /// Watches this cgroup for out-of-memory events.
+    ///
+    /// On v1 this registers for `memory.oom_control` notifications via `cgroup.event_control`
+    /// and an `eventfd`. On v2 it watches the `oom` counter of `memory.events` via `inotify`.
+    pub fn watch_oom(&self) -> io::Result<EventStream> {
+        match self.version {
+            CgroupVersion::V1 => {
+                let event_fd = register_v1_event(self, "memory.oom_control", None)?;
+                Ok(EventStream {
+                    kind: EventStreamKind::V1EventFd {
+                        event_fd,
+                        event: MemoryEvent::Oom,
+                    },
+                })
+            }
+            CgroupVersion::V2 => register_v2_event(self, "oom", MemoryEvent::Oom),
+        }
+    }
+}
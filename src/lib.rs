@@ -30,11 +30,53 @@ use std::path::{Path, PathBuf};
 
 use nix;
 
+mod builder;
+mod events;
+mod stats;
+pub use builder::{CgroupBuilder, CgroupConfigurationError};
+pub use events::{EventStream, MemoryEvent};
+pub use stats::{CpuStats, MemoryStats, PidStats, StatsProvider};
+
+/// The cgroups hierarchy layout in use on the system.
+///
+/// Linux has two incompatible cgroups layouts: the legacy per-controller (v1) hierarchy, where
+/// each subsystem is mounted separately, and the unified (v2) hierarchy, where a single mount
+/// exposes every controller. [`CgroupVersion::detect`] tells the two apart so that [`Cgroup`] can
+/// pick the right directory shape and control file names.
+///
+/// [`CgroupVersion::detect`]: enum.CgroupVersion.html#method.detect
+/// [`Cgroup`]: struct.Cgroup.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupVersion {
+    /// The legacy hierarchy: one mount per subsystem, e.g. `mount_point/memory/name`.
+    V1,
+    /// The unified hierarchy: a single mount shared by every subsystem, e.g. `mount_point/name`.
+    V2,
+}
+
+impl CgroupVersion {
+    /// Detects the cgroups version in use at `mount_point`.
+    ///
+    /// The unified hierarchy always exposes a `cgroup.controllers` file at its root, which never
+    /// exists on the legacy per-controller mounts, so its presence is used as the discriminator.
+    pub fn detect<P>(mount_point: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        if mount_point.as_ref().join("cgroup.controllers").is_file() {
+            CgroupVersion::V2
+        } else {
+            CgroupVersion::V1
+        }
+    }
+}
+
 /// A common structure holding a cgroups name (path).
 #[derive(Debug)]
 pub struct CgroupName {
     mount_point: PathBuf,
     name: PathBuf,
+    version: CgroupVersion,
 }
 
 impl CgroupName {
@@ -44,21 +86,127 @@ impl CgroupName {
     /// * It does not create any cgroups. It is just an API abstraction layer. Learn more about
     /// [`Cgroup::new`], [`Cgroup::create`], [`Cgroup::remove`], and [`AutomanagedCgroup::init`]
     /// methods.
+    /// * The cgroups filesystem mount point is auto-discovered from `/proc/self/mountinfo`
+    /// (falling back to `/sys/fs/cgroup` if discovery fails), see
+    /// [`CgroupName::with_discovered_mount_point`]. Use [`CgroupName::with_mount_point`] to
+    /// bypass discovery and pin an explicit mount point.
     ///
     /// [`Cgroup::new`]: struct.Cgroup.html#method.new
     /// [`Cgroup::create`]: struct.Cgroup.html#method.create
     /// [`Cgroup::remove`]: struct.Cgroup.html#method.remove
     /// [`AutomanagedCgroup::init`]: struct.AutomanagedCgroup.html#method.init
+    /// [`CgroupName::with_discovered_mount_point`]: #method.with_discovered_mount_point
+    /// [`CgroupName::with_mount_point`]: #method.with_mount_point
     pub fn new<P>(name: P) -> Self
     where
         P: AsRef<Path>,
     {
+        Self::with_discovered_mount_point(name)
+    }
+
+    /// Defines a new cgroups name, auto-discovering the cgroups filesystem mount point from
+    /// `/proc/self/mountinfo`.
+    ///
+    /// This supports non-standard mount locations (e.g. inside containers and sandboxes) and
+    /// falls back to `/sys/fs/cgroup` if no cgroup/cgroup2 mount is found in `mountinfo`.
+    ///
+    /// Notes:
+    /// * It does not create any cgroups, see [`CgroupName::new`] for details.
+    ///
+    /// [`CgroupName::new`]: #method.new
+    pub fn with_discovered_mount_point<P>(name: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let mount_point = discover_mount_point().unwrap_or_else(|_| "/sys/fs/cgroup".into());
+        Self::with_mount_point(name, mount_point)
+    }
+
+    /// Defines a new cgroups name rooted at an explicit `mount_point`, auto-detecting whether it
+    /// is a v1 or v2 hierarchy.
+    ///
+    /// Notes:
+    /// * It does not create any cgroups, see [`CgroupName::new`] for details.
+    ///
+    /// [`CgroupName::new`]: #method.new
+    pub fn with_mount_point<P, M>(name: P, mount_point: M) -> Self
+    where
+        P: AsRef<Path>,
+        M: AsRef<Path>,
+    {
+        let mount_point = mount_point.as_ref().to_path_buf();
+        let version = CgroupVersion::detect(&mount_point);
         Self {
-            // TODO: auto-discover the cgroups FS mount-point
-            mount_point: "/sys/fs/cgroup".into(),
+            mount_point,
             name: name.as_ref().to_path_buf(),
+            version,
         }
     }
+
+    /// Returns the detected cgroups hierarchy version for this name.
+    pub fn version(&self) -> CgroupVersion {
+        self.version
+    }
+}
+
+/// Finds the cgroups filesystem mount point by parsing `/proc/self/mountinfo`.
+///
+/// A v2 (unified) `cgroup2` mount is preferred when present. Otherwise, the parent directory of
+/// the first v1 per-controller `cgroup` mount is used, since v1 controllers are conventionally
+/// mounted as siblings under a common directory (e.g. `/sys/fs/cgroup/memory`,
+/// `/sys/fs/cgroup/cpu,cpuacct`, ... under `/sys/fs/cgroup`).
+fn discover_mount_point() -> io::Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let mut v1_mount_point = None;
+    for line in mountinfo.lines() {
+        // Format (see proc(5)): "... mount_point ... - fs_type mount_source super_options".
+        let mut halves = line.splitn(2, " - ");
+        let mount_point = halves
+            .next()
+            .and_then(|fields| fields.split_whitespace().nth(4));
+        let fs_type = halves
+            .next()
+            .and_then(|fields| fields.split_whitespace().next());
+        let mount_point = match mount_point {
+            Some(mount_point) => mount_point,
+            None => continue,
+        };
+        match fs_type {
+            Some("cgroup2") => return Ok(PathBuf::from(mount_point)),
+            Some("cgroup") => {
+                if v1_mount_point.is_none() {
+                    let parent = Path::new(mount_point).parent().unwrap_or(Path::new(mount_point));
+                    v1_mount_point = Some(parent.to_path_buf());
+                }
+            }
+            _ => {}
+        }
+    }
+    v1_mount_point.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no cgroup/cgroup2 mount point found in /proc/self/mountinfo",
+        )
+    })
+}
+
+/// The state of the `freezer` controller.
+///
+/// Writing [`Frozen`] stops every task in the cgroup from running (and, crucially, from forking)
+/// until [`Thawed`] is written back. See [`Cgroup::freeze`], [`Cgroup::thaw`], and
+/// [`Cgroup::kill_all_tasks_with_freezer`].
+///
+/// [`Frozen`]: #variant.Frozen
+/// [`Thawed`]: #variant.Thawed
+/// [`Cgroup::freeze`]: struct.Cgroup.html#method.freeze
+/// [`Cgroup::thaw`]: struct.Cgroup.html#method.thaw
+/// [`Cgroup::kill_all_tasks_with_freezer`]: struct.Cgroup.html#method.kill_all_tasks_with_freezer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezerState {
+    /// Every task in the cgroup is stopped and cannot run or spawn new children.
+    Frozen,
+    /// Tasks in the cgroup run normally.
+    Thawed,
 }
 
 /// A controller of a specific cgroups namespace.
@@ -67,6 +215,8 @@ impl CgroupName {
 #[derive(Debug)]
 pub struct Cgroup {
     root: PathBuf,
+    version: CgroupVersion,
+    subsystem: String,
 }
 
 impl Cgroup {
@@ -75,24 +225,68 @@ impl Cgroup {
     /// Notes:
     /// * It does not create any cgroups. It is just an API abstraction layer. Learn more about
     /// [`Cgroup::create`], [`Cgroup::remove`], and [`AutomanagedCgroup::init`] methods.
+    /// * On a v2 (unified) hierarchy `subsystem` is not part of the cgroup's path, but it is
+    /// still required: [`Cgroup::create`] uses it to enable the matching controller in the
+    /// parent's `cgroup.subtree_control`.
     ///
     /// [`Cgroup::create`]: #method.create
     /// [`Cgroup::remove`]: #method.remove
     /// [`AutomanagedCgroup::init`]: struct.AutomanagedCgroup.html#method.init
     pub fn new(cgroup_name: &CgroupName, subsystem: &str) -> Self {
-        Self {
-            root: cgroup_name
+        let root = match cgroup_name.version {
+            CgroupVersion::V1 => cgroup_name
                 .mount_point
                 .join(subsystem)
                 .join(&cgroup_name.name),
+            CgroupVersion::V2 => cgroup_name.mount_point.join(&cgroup_name.name),
+        };
+        Self {
+            root,
+            version: cgroup_name.version,
+            subsystem: subsystem.to_string(),
         }
     }
 
+    /// Returns the cgroups hierarchy version this cgroup belongs to.
+    pub fn version(&self) -> CgroupVersion {
+        self.version
+    }
+
+    /// Enables `self.subsystem` in the parent directory's `cgroup.subtree_control`.
+    ///
+    /// This is only meaningful (and only attempted) on a v2 hierarchy: a controller must be
+    /// enabled on a parent before it can be used by any of its children.
+    fn enable_controller_on_parent(&self) -> io::Result<()> {
+        if self.version != CgroupVersion::V2 {
+            return Ok(());
+        }
+        let parent = match self.root.parent() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        let subtree_control = parent.join("cgroup.subtree_control");
+        if !subtree_control.is_file() {
+            return Ok(());
+        }
+        fs::write(&subtree_control, format!("+{}", self.subsystem)).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Controller {:?} cannot be enabled under {:?} due to: {}",
+                    self.subsystem, subtree_control, error
+                ),
+            )
+        })
+    }
+
     /// Creates a cgroups namespace.
     ///
     /// Notes:
     /// * Keep in mind the usual filesystem permissions (owner, group, and mode bits).
+    /// * On a v2 hierarchy this also enables `subsystem` in the parent's
+    /// `cgroup.subtree_control`, as required by the kernel before a child cgroup may use it.
     pub fn create(&self) -> io::Result<()> {
+        self.enable_controller_on_parent()?;
         fs::create_dir(&self.root).map_err(|error| {
             io::Error::new(
                 error.kind(),
@@ -171,8 +365,17 @@ impl Cgroup {
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "could not parse the value"))
     }
 
+    /// The control file used to attach and list tasks: `tasks` on v1, `cgroup.procs` on v2
+    /// (which has no per-thread `tasks` file).
+    fn tasks_file_name(&self) -> &'static str {
+        match self.version {
+            CgroupVersion::V1 => "tasks",
+            CgroupVersion::V2 => "cgroup.procs",
+        }
+    }
+
     fn tasks_absolute_path(&self) -> PathBuf {
-        self.root.join("tasks")
+        self.root.join(self.tasks_file_name())
     }
 
     /// Attaches a task (thread) to the cgroup.
@@ -215,6 +418,53 @@ impl Cgroup {
         Ok(tasks_count)
     }
 
+    fn procs_absolute_path(&self) -> PathBuf {
+        self.root.join("cgroup.procs")
+    }
+
+    /// Attaches a whole process (all of its threads, atomically) to the cgroup via
+    /// `cgroup.procs`, unlike [`add_task`] which on v1 moves only the single thread `pid`.
+    ///
+    /// [`add_task`]: #method.add_task
+    pub fn add_process(&self, pid: nix::unistd::Pid) -> io::Result<()> {
+        fs::write(self.procs_absolute_path(), pid.to_string()).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "A process cannot be added to cgroup {:?} due to: {}",
+                    self.root, error
+                ),
+            )
+        })
+    }
+
+    /// Lists the processes (not individual threads) attached to the cgroup.
+    pub fn get_processes(&self) -> io::Result<Vec<nix::unistd::Pid>> {
+        Ok(fs::read_to_string(self.procs_absolute_path())
+            .map_err(|error| {
+                io::Error::new(
+                    error.kind(),
+                    format!(
+                        "Processes cannot be read from cgroup {:?} due to: {}",
+                        self.root, error
+                    ),
+                )
+            })?
+            .split_whitespace()
+            .map(|pid| nix::unistd::Pid::from_raw(pid.parse().unwrap()))
+            .collect())
+    }
+
+    /// Sends a specified Unix Signal to all the processes in the Cgroup.
+    pub fn send_signal_to_all_processes(&self, signal: nix::sys::signal::Signal) -> io::Result<usize> {
+        let processes = self.get_processes()?;
+        let processes_count = processes.len();
+        for process in processes {
+            nix::sys::signal::kill(process, signal).ok();
+        }
+        Ok(processes_count)
+    }
+
     /// Kills (SIGKILL) all the attached to the cgroup tasks.
     ///
     /// WARNING: The naive implementation turned out to be not reliable enough for the fork-bomb
@@ -236,6 +486,116 @@ impl Cgroup {
             "child subprocess(es) survived SIGKILL",
         ))
     }
+
+    /// The freezer control file: `freezer.state` on v1, `cgroup.freeze` on v2.
+    fn freezer_state_file(&self) -> &'static str {
+        match self.version {
+            CgroupVersion::V1 => "freezer.state",
+            CgroupVersion::V2 => "cgroup.freeze",
+        }
+    }
+
+    /// Sets the freezer state of this cgroup (it must be a `freezer` controller cgroup).
+    ///
+    /// See [`Cgroup::freeze`] and [`Cgroup::thaw`] for the common case.
+    ///
+    /// [`Cgroup::freeze`]: #method.freeze
+    /// [`Cgroup::thaw`]: #method.thaw
+    pub fn set_freezer_state(&self, state: FreezerState) -> io::Result<()> {
+        let value = match (self.version, state) {
+            (CgroupVersion::V1, FreezerState::Frozen) => "FROZEN",
+            (CgroupVersion::V1, FreezerState::Thawed) => "THAWED",
+            (CgroupVersion::V2, FreezerState::Frozen) => "1",
+            (CgroupVersion::V2, FreezerState::Thawed) => "0",
+        };
+        self.set_raw_value(self.freezer_state_file(), value)
+    }
+
+    /// Freezes every task in this cgroup: they stop running and cannot fork.
+    pub fn freeze(&self) -> io::Result<()> {
+        self.set_freezer_state(FreezerState::Frozen)
+    }
+
+    /// Thaws a previously frozen cgroup, letting its tasks run again.
+    pub fn thaw(&self) -> io::Result<()> {
+        self.set_freezer_state(FreezerState::Thawed)
+    }
+
+    /// Reports whether this (freezer) cgroup has actually reached the `Frozen` state.
+    ///
+    /// On v1 that means `freezer.state` reads `FROZEN` (as opposed to `THAWED` or the
+    /// in-between `FREEZING`); on v2 it means `cgroup.events` reports `frozen 1`.
+    fn is_frozen(&self) -> io::Result<bool> {
+        match self.version {
+            CgroupVersion::V1 => Ok(self.get_raw_value("freezer.state")?.trim_end() == "FROZEN"),
+            CgroupVersion::V2 => Ok(self
+                .get_raw_value("cgroup.events")?
+                .lines()
+                .any(|line| line.trim() == "frozen 1")),
+        }
+    }
+
+    /// Blocks until this (freezer) cgroup reaches the `Frozen` state.
+    ///
+    /// Freezing is asynchronous on both hierarchies: v1 transitions `THAWED` -> `FREEZING` ->
+    /// `FROZEN`, and v2's `cgroup.freeze` returns before `cgroup.events`'s `frozen 1` lands. A
+    /// caller that acts right after [`freeze`] without waiting for this can still observe tasks
+    /// that run (and fork) in that window.
+    ///
+    /// [`freeze`]: #method.freeze
+    fn wait_until_frozen(&self) -> io::Result<()> {
+        for _ in 0..1_000 {
+            if self.is_frozen()? {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_micros(200));
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "cgroup did not reach the frozen state in time",
+        ))
+    }
+
+    /// Reliably kills every task attached to this cgroup, defeating fork bombs.
+    ///
+    /// [`kill_all_tasks`] loses a race against a rapidly-forking process: a `SIGKILL` signal
+    /// round can complete while a task spawned in between survives unsignalled. This instead
+    /// uses `freezer` to stop all forking before signalling:
+    ///
+    /// 1. freeze `freezer` and wait until it actually reports frozen, so no task in this cgroup
+    ///    can run or fork any more,
+    /// 2. `SIGKILL` every task currently in this cgroup,
+    /// 3. thaw `freezer`, so the kernel can reap the killed tasks,
+    /// 4. repeat until no task is left.
+    ///
+    /// `freezer` must be the `freezer` subsystem [`Cgroup`] for the same cgroup name as `self`.
+    /// It is always thawed again before returning, even on error, since a cgroup left frozen
+    /// forever turns its tasks into unkillable zombies.
+    ///
+    /// [`kill_all_tasks`]: #method.kill_all_tasks
+    /// [`Cgroup`]: struct.Cgroup.html
+    pub fn kill_all_tasks_with_freezer(&self, freezer: &Cgroup) -> io::Result<()> {
+        let result = (|| {
+            for _ in 0..100 {
+                freezer.freeze()?;
+                freezer.wait_until_frozen()?;
+                let tasks = self.get_tasks()?;
+                if tasks.is_empty() {
+                    return Ok(());
+                }
+                for task in tasks {
+                    nix::sys::signal::kill(task, nix::sys::signal::Signal::SIGKILL).ok();
+                }
+                freezer.thaw()?;
+            }
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "child subprocess(es) survived SIGKILL even behind the freezer",
+            ))
+        })();
+        freezer.thaw().ok();
+        result
+    }
 }
 
 /// An automatically managed controller of a specific cgroups subsystem.
@@ -330,14 +690,14 @@ impl CgroupsCommandExt for std::process::Command {
     /// );
     /// ```
     fn cgroups(&mut self, cgroups: &[impl AsRef<Cgroup>]) -> &mut Self {
-        let tasks_paths = cgroups
+        let procs_paths = cgroups
             .iter()
-            .map(|cgroup| cgroup.as_ref().tasks_absolute_path())
+            .map(|cgroup| cgroup.as_ref().procs_absolute_path())
             .collect::<Vec<PathBuf>>();
         self.before_exec(move || {
             let pid = std::process::id().to_string();
-            for tasks_path in &tasks_paths {
-                fs::write(tasks_path, &pid)?;
+            for procs_path in &procs_paths {
+                fs::write(procs_path, &pid)?;
             }
             Ok(())
         })
@@ -0,0 +1,140 @@
+//! Typed readers for common controller statistics files, built on top of [`Cgroup::get_value`]
+//! and [`Cgroup::get_raw_value`].
+//!
+//! [`Cgroup::get_value`]: ../struct.Cgroup.html#method.get_value
+//! [`Cgroup::get_raw_value`]: ../struct.Cgroup.html#method.get_raw_value
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::{Cgroup, CgroupVersion};
+
+/// Memory controller statistics.
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    /// Current memory usage, in bytes (`memory.usage_in_bytes` on v1, `memory.current` on v2).
+    pub usage_bytes: u64,
+    /// Peak recorded memory usage, in bytes. Only tracked on v1 (`memory.max_usage_in_bytes`);
+    /// `None` on v2, which has no equivalent file.
+    pub max_usage_bytes: Option<u64>,
+    /// Memory limit, in bytes (`memory.limit_in_bytes` on v1, `memory.max` on v2). `None` if the
+    /// file reads `max` (v2's "no limit"); v1 reports an unset limit as a very large number,
+    /// which is returned as is.
+    pub limit_bytes: Option<u64>,
+    /// The flat key/value pairs parsed from `memory.stat`, e.g. `rss` -> 12345.
+    pub stat: HashMap<String, u64>,
+}
+
+/// CPU accounting controller statistics.
+#[derive(Debug, Clone)]
+pub struct CpuStats {
+    /// Total CPU time consumed, in microseconds (`cpuacct.usage` on v1, `cpu.stat`'s
+    /// `usage_usec` on v2).
+    pub usage_usec: u64,
+    /// Number of periods tasks were throttled. Only reported by v2's `cpu.stat`; `None` on v1.
+    pub nr_throttled: Option<u64>,
+    /// Total time tasks spent throttled, in microseconds. Only reported by v2's `cpu.stat`;
+    /// `None` on v1.
+    pub throttled_usec: Option<u64>,
+}
+
+/// PIDs controller statistics.
+#[derive(Debug, Clone)]
+pub struct PidStats {
+    /// Number of tasks currently in the cgroup (`pids.current`).
+    pub current: u64,
+    /// Maximum number of tasks allowed in the cgroup (`pids.max`), if a limit is set.
+    pub max: Option<u64>,
+}
+
+/// Parses typed statistics out of a cgroup's controller files.
+///
+/// Implemented for [`Cgroup`] so callers can read e.g. `cgroup.memory_stats()?` rather than
+/// threading stringly-typed keys, and v1/v2 file name differences, through [`Cgroup::get_value`]
+/// by hand.
+///
+/// [`Cgroup`]: ../struct.Cgroup.html
+/// [`Cgroup::get_value`]: ../struct.Cgroup.html#method.get_value
+pub trait StatsProvider {
+    /// Reads memory controller statistics.
+    fn memory_stats(&self) -> io::Result<MemoryStats>;
+
+    /// Reads CPU accounting controller statistics.
+    fn cpu_stats(&self) -> io::Result<CpuStats>;
+
+    /// Reads PIDs controller statistics.
+    fn pid_stats(&self) -> io::Result<PidStats>;
+}
+
+/// Parses the flat `key value` lines used by `memory.stat` and `cpu.stat`.
+fn parse_flat_stat(raw: &str) -> HashMap<String, u64> {
+    raw.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let key = fields.next()?;
+            let value = fields.next()?.parse().ok()?;
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Parses a limit file that is either a number or the literal `max` (meaning "no limit").
+fn parse_optional_limit(raw: &str) -> Option<u64> {
+    match raw.trim_end() {
+        "max" => None,
+        raw => raw.parse().ok(),
+    }
+}
+
+impl StatsProvider for Cgroup {
+    fn memory_stats(&self) -> io::Result<MemoryStats> {
+        let (usage_key, limit_key, max_usage_key) = match self.version() {
+            CgroupVersion::V1 => (
+                "memory.usage_in_bytes",
+                "memory.limit_in_bytes",
+                Some("memory.max_usage_in_bytes"),
+            ),
+            CgroupVersion::V2 => ("memory.current", "memory.max", None),
+        };
+        let usage_bytes = self.get_value(usage_key)?;
+        let limit_bytes = parse_optional_limit(&self.get_raw_value(limit_key)?);
+        let max_usage_bytes = match max_usage_key {
+            Some(key) => Some(self.get_value(key)?),
+            None => None,
+        };
+        let stat = parse_flat_stat(&self.get_raw_value("memory.stat")?);
+        Ok(MemoryStats {
+            usage_bytes,
+            max_usage_bytes,
+            limit_bytes,
+            stat,
+        })
+    }
+
+    fn cpu_stats(&self) -> io::Result<CpuStats> {
+        match self.version() {
+            CgroupVersion::V1 => {
+                let usage_nsec: u64 = self.get_value("cpuacct.usage")?;
+                Ok(CpuStats {
+                    usage_usec: usage_nsec / 1_000,
+                    nr_throttled: None,
+                    throttled_usec: None,
+                })
+            }
+            CgroupVersion::V2 => {
+                let stat = parse_flat_stat(&self.get_raw_value("cpu.stat")?);
+                Ok(CpuStats {
+                    usage_usec: stat.get("usage_usec").copied().unwrap_or(0),
+                    nr_throttled: stat.get("nr_throttled").copied(),
+                    throttled_usec: stat.get("throttled_usec").copied(),
+                })
+            }
+        }
+    }
+
+    fn pid_stats(&self) -> io::Result<PidStats> {
+        let current = self.get_value("pids.current")?;
+        let max = parse_optional_limit(&self.get_raw_value("pids.max")?);
+        Ok(PidStats { current, max })
+    }
+}